@@ -1,32 +1,35 @@
 use crate::input::Input;
 use lazy_static::lazy_static;
 use memchr::memchr;
+use memchr::memchr_iter;
 use memchr::memmem::Finder;
 use regex::Regex;
 use smol_str::SmolStr;
+use std::borrow::Cow;
 use std::clone::Clone;
 use std::cmp::Eq;
 use std::cmp::PartialEq;
 
-const SINGLE_QUOTE: char = '\'';
-const DOUBLE_QUOTE: char = '"';
-const BACKSLASH: char = '\\';
-const SLASH: char = '/';
-const NEWLINE: char = '\n';
-const SPACE: char = ' ';
-const FEED: char = '\u{12}'; // \f
-const TAB: char = '\t';
-const CR: char = '\r';
-const OPEN_SQUARE: char = '[';
-const CLOSE_SQUARE: char = ']';
-const OPEN_PARENTHESES: char = '(';
-const CLOSE_PARENTHESES: char = ')';
-const OPEN_CURLY: char = '{';
-const CLOSE_CURLY: char = '}';
-const SEMICOLON: char = ';';
-const ASTERISK: char = '*';
-const COLON: char = ':';
-const AT: char = '@';
+const NUL: u8 = 0;
+const SINGLE_QUOTE: u8 = b'\'';
+const DOUBLE_QUOTE: u8 = b'"';
+const BACKSLASH: u8 = b'\\';
+const SLASH: u8 = b'/';
+const NEWLINE: u8 = b'\n';
+const SPACE: u8 = b' ';
+const FEED: u8 = 0x12; // \f
+const TAB: u8 = b'\t';
+const CR: u8 = b'\r';
+const OPEN_SQUARE: u8 = b'[';
+const CLOSE_SQUARE: u8 = b']';
+const OPEN_PARENTHESES: u8 = b'(';
+const CLOSE_PARENTHESES: u8 = b')';
+const OPEN_CURLY: u8 = b'{';
+const CLOSE_CURLY: u8 = b'}';
+const SEMICOLON: u8 = b';';
+const ASTERISK: u8 = b'*';
+const COLON: u8 = b':';
+const AT: u8 = b'@';
 
 lazy_static! {
   static ref RE_AT_END: Regex = Regex::new(r##"[\t\n\u{12}\r "#'()/;\[\\\]{}]"##).unwrap();
@@ -37,45 +40,87 @@ lazy_static! {
   static ref FINDER_END_OF_COMMENT: Finder<'static> = Finder::new("*/");
 }
 
+/// A 1-based line/column position, resolved from a byte offset into the
+/// source. `column` counts UTF-16 code units, matching postcss.js (whose
+/// source strings are UTF-16), rather than bytes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Position {
+  pub offset: usize,
+  pub line: usize,
+  pub column: usize,
+}
+
+/// A recoverable lexing problem, flagged on the offending token instead of
+/// aborting the parse. Mirrors the kinds of unclosed constructs PostCSS's
+/// tokenizer has always had to tolerate in the wild.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LexError {
+  UnterminatedString,
+  UnterminatedComment,
+  UnterminatedBracket,
+  BadBracket,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Token(
   pub SmolStr,
   pub SmolStr,
   pub Option<usize>,
   pub Option<usize>,
+  pub Option<LexError>,
 );
 
 impl Token {
   pub fn new(kind: &'static str, content: &str, pos: Option<usize>, next: Option<usize>) -> Token {
-    Token(kind.into(), content.into(), pos, next)
+    Token(kind.into(), content.into(), pos, next, None)
   }
 }
 
 #[derive(Debug)]
 pub struct Tokenizer<'a> {
-  css: &'a str,
+  css: Cow<'a, str>,
   ignore: bool,
   current_token: Token,
   length: usize,
   pos: usize,
   buffer: Vec<Token>,
   returned: Vec<Token>,
+  errors: Vec<(LexError, usize)>,
+  line: usize,
+  line_start: usize,
+  // UTF-16 code units consumed since `line_start`, kept in step with it by
+  // `track_lines` so `current_position` can report `column` in O(1) without
+  // rescanning the line on every call.
+  line_units: usize,
+  line_starts: Option<Vec<usize>>,
+  incomplete: bool,
 }
 
 impl<'a> Tokenizer<'a> {
-  pub fn new(input: &'a Input, ignore_errors: bool) -> Tokenizer {
-    let length = input.css.chars().count();
+  /// Builds a tokenizer directly from a CSS string, with no knowledge of
+  /// `Input` or file paths.
+  pub fn from_str(css: &'a str, ignore_errors: bool) -> Tokenizer<'a> {
     Tokenizer {
-      css: &input.css,
+      css: Cow::Borrowed(css),
       ignore: ignore_errors,
-      current_token: Token("".into(), String::new().into(), None, None),
-      length,
+      current_token: Token("".into(), String::new().into(), None, None, None),
+      length: css.len(),
       pos: 0,
       buffer: vec![],
       returned: vec![],
+      errors: vec![],
+      line: 1,
+      line_start: 0,
+      line_units: 0,
+      line_starts: None,
+      incomplete: false,
     }
   }
 
+  pub fn new(input: &'a Input, ignore_errors: bool) -> Tokenizer<'a> {
+    Tokenizer::from_str(&input.css, ignore_errors)
+  }
+
   #[inline]
   fn push(&mut self, t: Token) {
     self.buffer.push(t);
@@ -85,8 +130,86 @@ impl<'a> Tokenizer<'a> {
     self.pos
   }
 
-  pub fn unclosed(&self, what: &str) {
-    panic!("Unclosed {} {}", what, self.pos);
+  /// Line/column `Position` of the tokenizer's current offset, kept up to
+  /// date incrementally by `track_lines` as `next_token` advances. Right
+  /// after `next_token` this is where the token just produced ends (and the
+  /// next one starts), not where it starts. Cheaper than `position_at`,
+  /// since no lookup or rescan is needed.
+  pub fn current_position(&self) -> Position {
+    Position {
+      offset: self.pos,
+      line: self.line,
+      column: self.line_units + 1,
+    }
+  }
+
+  /// Bumps `line`/`line_start`/`line_units` for every newline and UTF-16
+  /// code unit in `self.css[from..to]`, so each call only scans the bytes
+  /// just consumed rather than rescanning from the start of the file.
+  fn track_lines(&mut self, from: usize, to: usize) {
+    if to <= from {
+      return;
+    }
+    let mut tail_start = from;
+    for i in memchr_iter(NEWLINE, self.css[from..to].as_bytes()) {
+      self.line += 1;
+      self.line_start = from + i + 1;
+      tail_start = self.line_start;
+      self.line_units = 0;
+    }
+    self.line_units += self.css[tail_start..to].encode_utf16().count();
+  }
+
+  /// Resolves the line/column `Position` of an arbitrary byte offset,
+  /// building (and caching) a line-start index on first use so repeated
+  /// lookups only pay for a binary search, not a rescan.
+  pub fn position_at(&mut self, offset: usize) -> Position {
+    if self.line_starts.is_none() {
+      let mut starts = vec![0];
+      starts.extend(memchr_iter(NEWLINE, self.css.as_bytes()).map(|i| i + 1));
+      self.line_starts = Some(starts);
+    }
+
+    let starts = self.line_starts.as_ref().unwrap();
+    let line = match starts.binary_search(&offset) {
+      Ok(i) => i,
+      Err(i) => i - 1,
+    };
+
+    Position {
+      offset,
+      line: line + 1,
+      column: self.css[starts[line]..offset].encode_utf16().count() + 1,
+    }
+  }
+
+  /// Flags a recoverable lexing error at the current position and returns it
+  /// for attachment to the in-flight token, instead of panicking. Recording
+  /// is suppressed when the tokenizer was built with `ignore_errors` or the
+  /// caller passed `ignore_unclosed`, matching the old "don't panic" knobs.
+  fn unclosed(&mut self, what: LexError, ignore_unclosed: bool) -> Option<LexError> {
+    if self.ignore || ignore_unclosed {
+      None
+    } else {
+      self.errors.push((what, self.pos));
+      Some(what)
+    }
+  }
+
+  /// Whether an unterminated string/bracket/`url(...)` should recover with
+  /// the old minimal ignore-mode short-circuit (true, matching upstream
+  /// postcss.js's `safe` mode) rather than extending to `self.length` so a
+  /// flagged-but-continuing parse swallows the rest of the buffered input
+  /// instead of retokenizing it as fresh CSS.
+  fn recovers_minimally(&self, ignore_unclosed: bool) -> bool {
+    self.ignore || ignore_unclosed
+  }
+
+  /// Drains and returns every lexing error recorded so far, leaving the
+  /// tokenizer's error log empty. Callers decide whether to report them or
+  /// simply let parsing continue on the best-effort tokens already produced.
+  pub fn take_errors(&mut self) -> Vec<(LexError, usize)> {
+    std::mem::take(&mut self.errors)
   }
 
   pub fn end_of_file(&self) -> bool {
@@ -99,53 +222,66 @@ impl<'a> Tokenizer<'a> {
 
   pub fn next_token(&mut self, ignore_unclosed: bool) -> Token {
     if !self.returned.is_empty() {
+      self.incomplete = false;
       return self.returned.pop().unwrap();
     }
 
-    let mut code = char_code_at(self.css, self.pos);
+    self.incomplete = false;
+    let start_pos = self.pos;
+    let mut code = char_code_at(&self.css, self.pos);
 
     match code {
       NEWLINE | SPACE | TAB | CR | FEED => {
         let mut next = self.pos;
         loop {
           next += 1;
-          code = char_code_at(self.css, next);
+          code = char_code_at(&self.css, next);
           if !(code == SPACE || code == NEWLINE || code == TAB || code == FEED) {
             break;
           }
         }
 
-        self.current_token = Token("space".into(), self.css[self.pos..next].into(), None, None);
+        if next >= self.length {
+          self.incomplete = true;
+        }
+
+        self.current_token = Token(
+          "space".into(),
+          self.css[self.pos..next].into(),
+          None,
+          None,
+          None,
+        );
 
         self.pos = next - 1;
       }
       OPEN_SQUARE => {
-        self.current_token = Token("[".into(), "[".into(), Some(self.pos), None);
+        self.current_token = Token("[".into(), "[".into(), Some(self.pos), None, None);
       }
       CLOSE_SQUARE => {
-        self.current_token = Token("]".into(), "]".into(), Some(self.pos), None);
+        self.current_token = Token("]".into(), "]".into(), Some(self.pos), None, None);
       }
       OPEN_CURLY => {
-        self.current_token = Token("{".into(), "{".into(), Some(self.pos), None);
+        self.current_token = Token("{".into(), "{".into(), Some(self.pos), None, None);
       }
       CLOSE_CURLY => {
-        self.current_token = Token("}".into(), "}".into(), Some(self.pos), None);
+        self.current_token = Token("}".into(), "}".into(), Some(self.pos), None, None);
       }
       COLON => {
-        self.current_token = Token(":".into(), ":".into(), Some(self.pos), None);
+        self.current_token = Token(":".into(), ":".into(), Some(self.pos), None, None);
       }
       SEMICOLON => {
-        self.current_token = Token(";".into(), ";".into(), Some(self.pos), None);
+        self.current_token = Token(";".into(), ";".into(), Some(self.pos), None, None);
       }
       CLOSE_PARENTHESES => {
-        self.current_token = Token(")".into(), ")".into(), Some(self.pos), None);
+        self.current_token = Token(")".into(), ")".into(), Some(self.pos), None, None);
       }
       OPEN_PARENTHESES => {
         let prev = match self.buffer.pop() {
           Some(b) => b.1,
           None => String::new().into(),
         };
-        let n = char_code_at(self.css, self.pos + 1);
+        let n = char_code_at(&self.css, self.pos + 1);
         if prev == "url"
           && n != SINGLE_QUOTE
           && n != DOUBLE_QUOTE
@@ -156,24 +292,27 @@ impl<'a> Tokenizer<'a> {
           && n != CR
         {
           let mut next = self.pos;
+          let mut error = None;
           loop {
             let mut escaped = false;
-            match index_of_char(self.css, ')', next + 1) {
+            match index_of_char(&self.css, CLOSE_PARENTHESES, next + 1) {
               Some(i) => {
                 next = i;
               }
               None => {
-                if self.ignore || ignore_unclosed {
-                  next = self.pos;
-                  break;
+                self.incomplete = true;
+                error = self.unclosed(LexError::UnterminatedBracket, ignore_unclosed);
+                next = if self.recovers_minimally(ignore_unclosed) {
+                  self.pos
                 } else {
-                  self.unclosed("bracket")
-                }
+                  self.length
+                };
+                break;
               }
             }
 
             let mut escape_pos = next;
-            while char_code_at(self.css, escape_pos - 1) == BACKSLASH {
+            while char_code_at(&self.css, escape_pos - 1) == BACKSLASH {
               escape_pos -= 1;
               escaped = !escaped;
             }
@@ -185,52 +324,73 @@ impl<'a> Tokenizer<'a> {
 
           self.current_token = Token(
             "brackets".into(),
-            sub_string(self.css, self.pos, next + 1).into(),
+            sub_string(&self.css, self.pos, next + 1).into(),
             Some(self.pos),
             Some(next),
+            error,
           );
 
           self.pos = next;
         } else {
-          match index_of_char(self.css, ')', self.pos + 1) {
+          match index_of_char(&self.css, CLOSE_PARENTHESES, self.pos + 1) {
             Some(i) => {
               let content = &self.css[self.pos..i + 1];
 
               if RE_BAD_BRACKET.is_match(content) {
-                self.current_token = Token("(".into(), "(".into(), Some(self.pos), None);
+                let error = self.unclosed(LexError::BadBracket, ignore_unclosed);
+                self.current_token = Token("(".into(), "(".into(), Some(self.pos), None, error);
               } else {
                 self.current_token =
-                  Token("brackets".into(), content.into(), Some(self.pos), Some(i));
+                  Token("brackets".into(), content.into(), Some(self.pos), Some(i), None);
                 self.pos = i;
               }
             }
             None => {
-              self.current_token = Token("(".into(), "(".into(), Some(self.pos), None);
+              self.incomplete = true;
+              let error = self.unclosed(LexError::UnterminatedBracket, ignore_unclosed);
+              if self.recovers_minimally(ignore_unclosed) {
+                self.current_token = Token("(".into(), "(".into(), Some(self.pos), None, error);
+              } else {
+                let next = self.length;
+                self.current_token = Token(
+                  "brackets".into(),
+                  sub_string(&self.css, self.pos, next + 1).into(),
+                  Some(self.pos),
+                  Some(next),
+                  error,
+                );
+                self.pos = next;
+              }
             }
           };
         }
       }
       SINGLE_QUOTE | DOUBLE_QUOTE => {
-        let quote = if code == SINGLE_QUOTE { '\'' } else { '"' };
+        let quote = code;
         let mut next = self.pos;
+        let mut error = None;
         loop {
           let mut escaped = false;
-          match index_of_char(self.css, quote, next + 1) {
+          match index_of_char(&self.css, quote, next + 1) {
             Some(i) => {
               next = i;
             }
             None => {
-              if self.ignore || ignore_unclosed {
-                next = self.pos + 1;
-                break;
+              self.incomplete = true;
+              error = self.unclosed(LexError::UnterminatedString, ignore_unclosed);
+              next = if self.recovers_minimally(ignore_unclosed) {
+                // The quote plus the whole character after it, which may be
+                // more than one byte.
+                self.pos + self.css[self.pos + 1..].chars().next().map_or(1, char::len_utf8)
               } else {
-                self.unclosed("string")
-              }
+                self.length
+              };
+              break;
             }
           }
 
           let mut escape_pos = next;
-          while char_code_at(self.css, escape_pos - 1) == BACKSLASH {
+          while char_code_at(&self.css, escape_pos - 1) == BACKSLASH {
             escape_pos -= 1;
             escaped = !escaped;
           }
@@ -242,33 +402,38 @@ impl<'a> Tokenizer<'a> {
 
         self.current_token = Token(
           "string".into(),
-          sub_string(self.css, self.pos, next + 1).into(),
+          sub_string(&self.css, self.pos, next + 1).into(),
           Some(self.pos),
           Some(next),
+          error,
         );
         self.pos = next;
       }
       AT => {
         let next = match RE_AT_END.find_at(&self.css, self.pos + 1) {
           Some(mat) => mat.end() - 2,
-          None => self.length - 1,
+          None => {
+            self.incomplete = true;
+            self.length - 1
+          }
         };
         self.current_token = Token(
           "at-word".into(),
-          sub_string(self.css, self.pos, next + 1).into(),
+          sub_string(&self.css, self.pos, next + 1).into(),
           Some(self.pos),
           Some(next),
+          None,
         );
         self.pos = next;
       }
       BACKSLASH => {
         let mut next = self.pos;
         let mut escape = true;
-        while char_code_at(self.css, next + 1) == BACKSLASH {
+        while char_code_at(&self.css, next + 1) == BACKSLASH {
           next += 1;
           escape = !escape;
         }
-        code = char_code_at(self.css, next + 1);
+        code = char_code_at(&self.css, next + 1);
         if escape
           && code != SLASH
           && code != SPACE
@@ -278,59 +443,80 @@ impl<'a> Tokenizer<'a> {
           && code != FEED
         {
           next += 1;
-          if RE_HEX_ESCAPE.is_match(sub_string(self.css, next, next + 1)) {
-            while RE_HEX_ESCAPE.is_match(sub_string(self.css, next + 1, next + 2)) {
+          if next < self.length && !self.css.is_char_boundary(next + 1) {
+            // The escaped character is multi-byte: step to its last byte so
+            // the token ends on a character boundary.
+            while !self.css.is_char_boundary(next + 1) {
+              next += 1;
+            }
+          } else if RE_HEX_ESCAPE.is_match(sub_string(&self.css, next, next + 1)) {
+            while self.css.is_char_boundary(next + 2)
+              && RE_HEX_ESCAPE.is_match(sub_string(&self.css, next + 1, next + 2))
+            {
               next += 1;
             }
-            if char_code_at(self.css, next + 1) == SPACE {
+            if char_code_at(&self.css, next + 1) == SPACE {
               next += 1;
             }
           }
         }
 
+        if next + 1 >= self.length {
+          // The escape (or the trailing hex digits/space it may swallow)
+          // ran right up to the edge of buffered input, so more chunks
+          // could still extend it.
+          self.incomplete = true;
+        }
+
         self.current_token = Token(
           "word".into(),
-          sub_string(self.css, self.pos, next + 1).into(),
+          sub_string(&self.css, self.pos, next + 1).into(),
           Some(self.pos),
           Some(next),
+          None,
         );
         self.pos = next;
       }
       _ => {
-        self.pos = if code == SLASH && char_code_at(self.css, self.pos + 1) == ASTERISK {
-          let next = match index_of_end_comment(self.css, self.pos + 2) {
+        self.pos = if code == SLASH && char_code_at(&self.css, self.pos + 1) == ASTERISK {
+          let mut error = None;
+          let next = match index_of_end_comment(&self.css, self.pos + 2) {
             Some(i) => i + 1,
             None => {
-              if !self.ignore && !ignore_unclosed {
-                self.unclosed("comment");
-              }
+              self.incomplete = true;
+              error = self.unclosed(LexError::UnterminatedComment, ignore_unclosed);
               self.length
             }
           };
 
           self.current_token = Token(
             "comment".into(),
-            sub_string(self.css, self.pos, next + 1).into(),
+            sub_string(&self.css, self.pos, next + 1).into(),
             Some(self.pos),
             Some(next),
+            error,
           );
           next
         } else {
           let next = match RE_WORD_END.find_at(&self.css, self.pos + 1) {
             Some(mat) => {
-              if char_code_at(&self.css, mat.end() - 2) == '/' {
+              if char_code_at(&self.css, mat.end() - 2) == SLASH {
                 mat.end() - 3
               } else {
                 mat.end() - 2
               }
             }
-            None => self.length - 1,
+            None => {
+              self.incomplete = true;
+              self.length - 1
+            }
           };
           self.current_token = Token(
             "word".into(),
-            sub_string(self.css, self.pos, next + 1).into(),
+            sub_string(&self.css, self.pos, next + 1).into(),
             Some(self.pos),
             Some(next),
+            None,
           );
           self.push(self.current_token.clone());
           next
@@ -339,8 +525,92 @@ impl<'a> Tokenizer<'a> {
     }
 
     self.pos += 1;
+    if self.pos > self.length {
+      // An unterminated comment/string that fell back to `self.length` (the
+      // word/at-word branches already stop one short, at `self.length - 1`,
+      // for the same reason) would otherwise push `pos` one past the end of
+      // `self.css`, and `track_lines` below slices up to `pos`.
+      self.pos = self.length;
+    }
+    self.track_lines(start_pos, self.pos);
     self.current_token.clone()
   }
+
+  /// Feeds another chunk of CSS into the tokenizer and returns every
+  /// complete token that could be extracted, plus the total number of bytes
+  /// consumed so far across all chunks. A trailing construct that might
+  /// still be open when the chunk runs out (an unterminated string,
+  /// comment, or `url(...)` bracket) is left unconsumed and retried after
+  /// the next `feed` call, so the tokenizer never has to guess whether a
+  /// partial token is actually malformed. Tokens that complete within the
+  /// buffered input are recorded with the usual `ignore_unclosed` semantics
+  /// (so a fully-present `BadBracket` isn't silently dropped just because
+  /// streaming is in use); only errors belonging to a rolled-back, not-yet-
+  /// complete token are discarded. Calling the ordinary
+  /// `next_token`/`end_of_file` API once the last chunk has been fed
+  /// resumes from where `feed` left off and reports anything still open at
+  /// true end-of-input.
+  ///
+  /// Each chunk is appended to an internally owned buffer (`self.css`
+  /// switches to `Cow::Owned` on first use). Nothing is dropped from the
+  /// front of that buffer as tokens are consumed, so after many chunks the
+  /// tokenizer holds the whole input in memory, the same as `from_str`
+  /// would; `feed` saves repeated re-scanning of already-tokenized input,
+  /// not memory, and does not yet bound memory use for arbitrarily large
+  /// streams.
+  pub fn feed(&mut self, chunk: &str) -> (Vec<Token>, usize) {
+    self.css.to_mut().push_str(chunk);
+    self.length = self.css.len();
+    self.line_starts = None;
+
+    let mut tokens = vec![];
+    while self.pos < self.length {
+      let pos = self.pos;
+      let line = self.line;
+      let line_start = self.line_start;
+      let line_units = self.line_units;
+      let buffer_len = self.buffer.len();
+      let errors_len = self.errors.len();
+      // `next_token` either pushes one word token onto `self.buffer` or pops
+      // at most one (the `url(` lookahead), never both, so remembering the
+      // tail entry is enough to undo either without cloning the whole
+      // buffer on every token.
+      let popped_hint = self.buffer.last().cloned();
+
+      let token = self.next_token(false);
+      if self.incomplete {
+        self.pos = pos;
+        self.line = line;
+        self.line_start = line_start;
+        self.line_units = line_units;
+        self.errors.truncate(errors_len);
+        if self.buffer.len() < buffer_len {
+          if let Some(tok) = popped_hint {
+            self.buffer.push(tok);
+          }
+        } else {
+          self.buffer.truncate(buffer_len);
+        }
+        break;
+      }
+
+      tokens.push(token);
+    }
+
+    (tokens, self.pos)
+  }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+  type Item = Token;
+
+  fn next(&mut self) -> Option<Token> {
+    if self.end_of_file() {
+      None
+    } else {
+      Some(self.next_token(false))
+    }
+  }
 }
 
 #[inline]
@@ -352,11 +622,15 @@ fn index_of_end_comment(value: &str, from_index: usize) -> Option<usize> {
 }
 
 #[inline]
-fn index_of_char(value: &str, search_value: char, from_index: usize) -> Option<usize> {
+fn index_of_char(value: &str, search_value: u8, from_index: usize) -> Option<usize> {
   let (_, last) = value.split_at(from_index);
-  memchr(search_value as u8, last.as_bytes()).map(|v| v + from_index)
+  memchr(search_value, last.as_bytes()).map(|v| v + from_index)
 }
 
+/// Slices `s[start..end]`, clamping `end` to `s.len()`. All callers derive
+/// `start`/`end` from ASCII delimiter bytes found via byte-oriented scans, so
+/// the bounds always land on UTF-8 character boundaries even though the
+/// content in between may contain multi-byte characters.
 #[inline]
 fn sub_string(s: &str, start: usize, end: usize) -> &str {
   if end + 1 > s.len() {
@@ -366,14 +640,18 @@ fn sub_string(s: &str, start: usize, end: usize) -> &str {
   }
 }
 
+/// Byte at `n`, or `NUL` past the end of `s`. Operating on bytes rather than
+/// `chars()` keeps this O(1) and keeps `n` comparable to `Tokenizer::pos`,
+/// which is itself a byte offset; every delimiter it's compared against
+/// (`{}()[];:@/\"'` and whitespace) is ASCII, so byte-for-byte comparison is
+/// correct even when the surrounding text contains multi-byte characters.
 #[inline]
-fn char_code_at(s: &str, n: usize) -> char {
+fn char_code_at(s: &str, n: usize) -> u8 {
   if n >= s.len() {
-    '\0'
+    NUL
   } else {
-    s.as_bytes()[n] as char
+    s.as_bytes()[n]
   }
-  // s.chars().nth(n).unwrap_or('\0')
 }
 
 #[cfg(test)]
@@ -383,8 +661,169 @@ mod test {
   #[test]
   fn test_char_code_at() {
     let s = "0123456789abc";
-    assert_eq!(char_code_at(s, 0), '0');
-    assert_eq!(char_code_at(s, 1), '1');
-    assert_eq!(char_code_at(s, 100), '\0');
+    assert_eq!(char_code_at(s, 0), b'0');
+    assert_eq!(char_code_at(s, 1), b'1');
+    assert_eq!(char_code_at(s, 100), NUL);
+  }
+
+  #[test]
+  fn test_char_code_at_multi_byte() {
+    let s = "a\u{1F600}b";
+    assert_eq!(char_code_at(s, 0), b'a');
+    // Byte offset 5 lands on the ASCII 'b' that follows the 4-byte emoji.
+    assert_eq!(char_code_at(s, 5), b'b');
+  }
+
+  fn drain(t: &mut Tokenizer<'_>) -> Vec<Token> {
+    let mut tokens = vec![];
+    while !t.end_of_file() {
+      tokens.push(t.next_token(false));
+    }
+    tokens
+  }
+
+  #[test]
+  fn test_unterminated_comment_does_not_panic() {
+    let mut t = Tokenizer::from_str("a { } /* unterminated comment", false);
+    drain(&mut t);
+    let errors = t.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, LexError::UnterminatedComment);
+  }
+
+  #[test]
+  fn test_unterminated_string_at_eof_does_not_panic() {
+    // The opening quote is the very last byte of the input.
+    let mut t = Tokenizer::from_str("a{\"", false);
+    drain(&mut t);
+    let errors = t.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, LexError::UnterminatedString);
+  }
+
+  #[test]
+  fn test_unterminated_string_swallows_rest_of_input() {
+    // A `}` inside an unterminated string must not be treated as closing
+    // the real block, and `b { color: red }` must not be retokenized as a
+    // sibling rule.
+    let css = "a { color: 'unterminated string literal continues here; more stuff } b { color: red }";
+    let mut t = Tokenizer::from_str(css, false);
+    let tokens = drain(&mut t);
+    let kinds: Vec<_> = tokens.iter().map(|tok| tok.0.as_str()).collect();
+    assert_eq!(
+      kinds,
+      vec!["word", "space", "{", "space", "word", ":", "space", "string"]
+    );
+    let string_token = tokens.last().unwrap();
+    assert!(string_token.1.ends_with("red }"));
+    let errors = t.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, LexError::UnterminatedString);
+  }
+
+  #[test]
+  fn test_unterminated_bracket_does_not_panic() {
+    let mut t = Tokenizer::from_str("a { background: url(foo", false);
+    let tokens = drain(&mut t);
+    let errors = t.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, LexError::UnterminatedBracket);
+    // "foo" must be part of the "brackets" token, not a separate trailing
+    // "word" token re-tokenized as if `url(` had never been opened.
+    let brackets = tokens.iter().find(|tok| tok.0 == "brackets").unwrap();
+    assert!(brackets.1.ends_with("foo"));
+    assert!(!tokens.iter().any(|tok| tok.0 == "word" && tok.1 == "foo"));
+  }
+
+  #[test]
+  fn test_column_counts_characters_not_bytes() {
+    // "café" is 4 characters but 5 bytes ('é' is 2 bytes in UTF-8).
+    let mut t = Tokenizer::from_str("café{\n}", false);
+    let word = t.next_token(false);
+    assert_eq!(word.0.as_str(), "word");
+    let pos = t.current_position();
+    assert_eq!(pos.line, 1);
+    assert_eq!(pos.column, 5);
+  }
+
+  #[test]
+  fn test_column_counts_utf16_units_for_astral_characters() {
+    // "a\u{1F600}b" is 3 chars but, like in postcss.js (UTF-16 strings),
+    // the emoji counts as 2 units, for 4 units total consumed by the word.
+    let mut t = Tokenizer::from_str("a\u{1F600}b", false);
+    let word = t.next_token(false);
+    assert_eq!(word.0.as_str(), "word");
+    let pos = t.current_position();
+    assert_eq!(pos.column, 5);
+  }
+
+  #[test]
+  fn test_position_at_counts_characters_not_bytes() {
+    let css = "café\nb";
+    let mut t = Tokenizer::from_str(css, false);
+    // Byte offset of 'b', on the second line.
+    let offset = css.len() - 1;
+    let pos = t.position_at(offset);
+    assert_eq!(pos.line, 2);
+    assert_eq!(pos.column, 1);
+    // Byte offset 5, just past "café" on the first line: 4 characters, so
+    // column 5 (counting bytes would give 6).
+    let pos = t.position_at(5);
+    assert_eq!(pos.line, 1);
+    assert_eq!(pos.column, 5);
+  }
+
+  #[test]
+  fn test_feed_chunked_matches_whole_input() {
+    let css = "a { color: red; } /* a comment */ b { color: blue; }";
+
+    let mut whole = Tokenizer::from_str(css, false);
+    let whole_tokens = drain(&mut whole);
+
+    let mut chunked = Tokenizer::from_str("", false);
+    let mut chunked_tokens = vec![];
+    for chunk in css.as_bytes().chunks(3) {
+      let (tokens, _) = chunked.feed(std::str::from_utf8(chunk).unwrap());
+      chunked_tokens.extend(tokens);
+    }
+    chunked_tokens.extend(drain(&mut chunked));
+
+    assert_eq!(whole_tokens, chunked_tokens);
+  }
+
+  #[test]
+  fn test_feed_does_not_panic_on_comment_split_across_chunks() {
+    // A chunk boundary landing inside a not-yet-closed comment used to
+    // overshoot `self.length` in `track_lines` and panic (see the
+    // `test_unterminated_comment_does_not_panic` regression above); feeding
+    // byte-by-byte guarantees such a boundary.
+    let css = "a { } /* unterminated comment";
+    let mut t = Tokenizer::from_str("", false);
+    for byte in css.as_bytes() {
+      t.feed(std::str::from_utf8(std::slice::from_ref(byte)).unwrap());
+    }
+    let tokens = drain(&mut t);
+    assert!(!tokens.is_empty());
+    let errors = t.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, LexError::UnterminatedComment);
+  }
+
+  #[test]
+  fn test_iterator_collects_all_tokens() {
+    let t = Tokenizer::from_str("a{}", false);
+    let kinds: Vec<_> = t.map(|tok| tok.0.to_string()).collect();
+    assert_eq!(kinds, vec!["word", "{", "}"]);
+  }
+
+  #[test]
+  fn test_from_str_is_a_standalone_entry_point() {
+    // No `Input` involved: from_str is the pure-lexing constructor.
+    let mut t = Tokenizer::from_str("a{b:c}", false);
+    let mut kinds = vec![];
+    while !t.end_of_file() {
+      kinds.push(t.next_token(false).0.to_string());
+    }
+    assert_eq!(kinds, vec!["word", "{", "word", ":", "word", "}"]);
   }
 }